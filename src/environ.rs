@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::fmt;
 
 use crate::spec;
 
@@ -8,19 +9,141 @@ pub type Env = BTreeMap<String, String>; // FIXME: Use OsString instead?
 
 //------------------------------------------------------------------------------
 
-pub fn build(start_env: std::env::Vars, spec: &spec::Env) -> Env {
-    start_env
+/// An error building a process environment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// `name`'s value (transitively) refers back to itself.
+    Cycle(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Cycle(name) => write!(f, "cyclic environment variable reference: ${{{}}}", name),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+//------------------------------------------------------------------------------
+
+/// Builds the environment for a process: the inherited environment per
+/// `spec.inherit`, with `spec.vars` layered on top, overriding any inherited
+/// variable of the same name.  Values in `spec.vars` may interpolate
+/// `${NAME}` references to the inherited environment or to other vars in
+/// `spec.vars`, resolved in dependency order rather than key order, so that
+/// e.g. `PATH=${PATH}:/opt/bin` and a var defined in terms of one defined
+/// later both resolve correctly.  A reference to a var that is itself
+/// (transitively) defined in terms of the original is a cycle and returns
+/// an error, rather than silently producing an empty or partial value.  A
+/// reference to a name that is undefined everywhere resolves to the empty
+/// string.
+pub fn build<I>(start_env: I, spec: &spec::Env) -> Result<Env, Error>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    // Start with the inherited environment, filtered per `spec.inherit`.
+    let inherited: Env = start_env
+        .into_iter()
         .filter(|(env_var, _)| match &spec.inherit {
             spec::EnvInherit::None => false,
             spec::EnvInherit::All => true,
             spec::EnvInherit::Vars(vars) => vars.contains(env_var),
         })
-        .chain(
-            (&spec.vars)
-                .into_iter()
-                .map(|(n, v)| (n.clone(), v.clone())),
-        )
-        .collect()
+        .collect();
+
+    // Resolve each spec var in dependency order: a var whose value
+    // references another spec var is resolved only after that other var,
+    // regardless of which sorts first alphabetically.  `resolving` tracks
+    // the chain of vars currently being resolved, to detect cycles.
+    let mut resolved = Env::new();
+    let mut resolving = Vec::new();
+    for name in spec.vars.keys() {
+        resolve_var(name, spec, &inherited, &mut resolved, &mut resolving)?;
+    }
+
+    let mut env = inherited;
+    env.extend(resolved);
+    Ok(env)
+}
+
+/// Resolves `name` in `spec.vars` into `resolved`, first recursively
+/// resolving any spec vars that `name`'s value references.  Does nothing if
+/// `name` isn't in `spec.vars` (e.g. it's inherited only) or is already
+/// resolved.  Returns `Error::Cycle` if resolving `name` would revisit a var
+/// already on `resolving`.
+fn resolve_var(
+    name: &str,
+    spec: &spec::Env,
+    inherited: &Env,
+    resolved: &mut Env,
+    resolving: &mut Vec<String>,
+) -> Result<(), Error> {
+    if resolved.contains_key(name) {
+        return Ok(());
+    }
+    if resolving.iter().any(|n| n == name) {
+        return Err(Error::Cycle(name.to_string()));
+    }
+    let Some(value) = spec.vars.get(name) else {
+        // Not a spec var; nothing to resolve here.
+        return Ok(());
+    };
+
+    resolving.push(name.to_string());
+    for ref_name in refs(value) {
+        // A reference to the var's own name is to the inherited value of
+        // that name (e.g. `PATH=${PATH}:/opt/bin`), not a cycle.
+        if ref_name != name && spec.vars.contains_key(ref_name) {
+            resolve_var(ref_name, spec, inherited, resolved, resolving)?;
+        }
+    }
+    resolving.pop();
+
+    // Every spec var this value depends on is now resolved, so interpolate
+    // against the inherited environment plus what's resolved so far.
+    let mut env = inherited.clone();
+    env.extend(resolved.iter().map(|(k, v)| (k.clone(), v.clone())));
+    resolved.insert(name.to_string(), interpolate(value, &env));
+    Ok(())
+}
+
+/// Returns the names referenced by `${NAME}` in `value`, in order.
+fn refs(value: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        match rest[start..].find('}') {
+            Some(len) => {
+                names.push(&rest[start + 2..start + len]);
+                rest = &rest[start + len + 1..];
+            }
+            None => break,
+        }
+    }
+    names
+}
+
+/// Replaces each `${NAME}` reference in `value` with the current value of
+/// `NAME` in `env`, or the empty string if `NAME` is unset.  An unterminated
+/// `${` is left as-is.
+fn interpolate(value: &str, env: &Env) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        match rest[start..].find('}') {
+            Some(len) => {
+                result.push_str(&rest[..start]);
+                let name = &rest[start + 2..start + len];
+                result.push_str(env.get(name).map_or("", |v| v.as_str()));
+                rest = &rest[start + len + 1..];
+            }
+            None => break,
+        }
+    }
+    result.push_str(rest);
+    result
 }
 
 //------------------------------------------------------------------------------
@@ -87,4 +210,73 @@ mod tests {
             },
         );
     }
+
+    #[test]
+    fn interpolate_no_refs() {
+        let env = Env::from([("FOO".to_string(), "42".to_string())]);
+        assert_eq!(interpolate("plain value", &env), "plain value");
+    }
+
+    #[test]
+    fn interpolate_ref() {
+        let env = Env::from([("FOO".to_string(), "42".to_string())]);
+        assert_eq!(interpolate("value is ${FOO}!", &env), "value is 42!");
+    }
+
+    #[test]
+    fn interpolate_unset_ref() {
+        let env = Env::new();
+        assert_eq!(interpolate("${MISSING}", &env), "");
+    }
+
+    #[test]
+    fn interpolate_unterminated_ref() {
+        let env = Env::new();
+        assert_eq!(interpolate("oops ${FOO", &env), "oops ${FOO");
+    }
+
+    #[test]
+    fn build_resolves_out_of_order_reference() {
+        // "A" sorts before "B" alphabetically, but depends on it; resolution
+        // must follow the dependency, not the key order.
+        let spec = spec::Env {
+            inherit: None,
+            vars: BTreeMap::from([
+                ("A".to_string(), "${B}".to_string()),
+                ("B".to_string(), "value".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let env = build(Vec::new(), &spec).unwrap();
+        assert_eq!(env.get("A"), Some(&"value".to_string()));
+        assert_eq!(env.get("B"), Some(&"value".to_string()));
+    }
+
+    #[test]
+    fn build_resolves_against_inherited_var() {
+        let spec = spec::Env {
+            inherit: All,
+            vars: BTreeMap::from([("PATH".to_string(), "${PATH}:/opt/bin".to_string())]),
+            ..Default::default()
+        };
+        let start_env = vec![("PATH".to_string(), "/usr/bin".to_string())];
+        let env = build(start_env, &spec).unwrap();
+        assert_eq!(env.get("PATH"), Some(&"/usr/bin:/opt/bin".to_string()));
+    }
+
+    #[test]
+    fn build_errors_on_cycle() {
+        let spec = spec::Env {
+            inherit: None,
+            vars: BTreeMap::from([
+                ("A".to_string(), "${B}".to_string()),
+                ("B".to_string(), "${A}".to_string()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            build(Vec::new(), &spec),
+            Err(Error::Cycle("A".to_string()))
+        );
+    }
 }
@@ -2,40 +2,192 @@ extern crate exitcode;
 
 mod argv;
 
+/// Under the `dhat-heap` feature, profile heap allocations for the lifetime
+/// of the process.  This has zero cost when the feature is off.
+#[cfg(feature = "dhat-heap")]
+#[global_allocator]
+static ALLOCATOR: dhat::Alloc = dhat::Alloc;
+
 use futures::future::join;
 // use procstar::fd::parse_fd;
 use procstar::http;
 use procstar::procs::{collect_results, start_procs, SharedRunningProcs};
 use procstar::res;
+use procstar::sig::Signum;
 use procstar::spec;
 use procstar::wsclient;
+use std::time::Duration;
+use tokio::sync::watch;
 
 //------------------------------------------------------------------------------
 
-async fn maybe_run_http(serve: bool, running_procs: SharedRunningProcs) {
+/// Default grace period between SIGTERM and SIGKILL when draining running
+/// procs on shutdown, used when `--shutdown-timeout` isn't given.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How procstar's own exit code is derived from the exit codes of the procs
+/// it ran, when not serving (i.e. it ran one or more specs and is about to
+/// exit once they've all completed).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCodePolicy {
+    /// Exit nonzero if any proc failed, zero otherwise.  The default.
+    AnyFailure,
+    /// Exit with the last proc's exit code, in proc id order.
+    Last,
+    /// Exit with the largest exit code among all procs.
+    Max,
+}
+
+impl Default for ExitCodePolicy {
+    fn default() -> Self {
+        ExitCodePolicy::AnyFailure
+    }
+}
+
+/// Exit code used for a proc that never produced a `status`, e.g. because
+/// it was aborted before it ran or we lost track of it.  A proc that itself
+/// exits with this code is not distinguished from one with no status.
+const NO_STATUS_EXIT_CODE: i32 = exitcode::UNAVAILABLE as i32;
+
+/// Exit code used for a proc that exited due to a signal, following the
+/// common shell convention of 128 + signal number.
+const SIGNAL_EXIT_CODE_BASE: i32 = 128;
+
+/// Computes procstar's own exit code from `result` according to `policy`.
+fn compute_exit_code(result: &res::Res, policy: ExitCodePolicy) -> i32 {
+    let exit_code_of = |proc_res: &res::ProcRes| -> i32 {
+        match &proc_res.status {
+            Some(status) => status
+                .code()
+                .unwrap_or_else(|| SIGNAL_EXIT_CODE_BASE + status.signal().unwrap_or(0)),
+            None => NO_STATUS_EXIT_CODE,
+        }
+    };
+
+    match policy {
+        ExitCodePolicy::AnyFailure => {
+            if result
+                .values()
+                .all(|proc_res| proc_res.status.as_ref().map_or(false, |s| s.success()))
+            {
+                exitcode::OK
+            } else {
+                1
+            }
+        }
+        // `result` is a `BTreeMap` keyed by proc id, so "last" means last in
+        // proc id order rather than start order.
+        ExitCodePolicy::Last => result
+            .values()
+            .next_back()
+            .map_or(exitcode::OK, exit_code_of),
+        ExitCodePolicy::Max => result
+            .values()
+            .map(exit_code_of)
+            .max()
+            .unwrap_or(exitcode::OK),
+    }
+}
+
+async fn maybe_run_http(
+    serve: bool,
+    running_procs: SharedRunningProcs,
+    shutdown: watch::Receiver<bool>,
+) {
     if serve {
-        http::run_http(running_procs).await.unwrap(); // FIXME: unwrap
+        // `run_http` selects its accept loop against `shutdown`, so
+        // in-flight requests finish rather than being dropped when we stop
+        // accepting new ones.
+        http::run_http(running_procs, shutdown).await.unwrap(); // FIXME: unwrap
     }
 }
 
-async fn maybe_run_ws(url: Option<String>, running_procs: SharedRunningProcs) {
+async fn maybe_run_ws(
+    url: Option<String>,
+    running_procs: SharedRunningProcs,
+    shutdown: watch::Receiver<bool>,
+) {
     if let Some(url) = url {
         let url = url::Url::parse(&url).unwrap(); // FIXME: unwrap
-        let (_connection, handler) = wsclient::Connection::connect(&url).await.unwrap(); // FIXME: unwrap
-        handler.run(running_procs.clone()).await.unwrap(); // FIXME: unwrap
+        let connection = wsclient::Connection::new(&url, None, None);
+        // `run` selects its reconnect loop against `shutdown`, so it stops
+        // retrying and returns instead of reconnecting forever.
+        wsclient::run(connection, running_procs.clone(), shutdown)
+            .await
+            .unwrap(); // FIXME: unwrap
+    }
+}
+
+/// Waits for SIGINT or SIGTERM, then immediately broadcasts on `shutdown` so
+/// the HTTP/WS servers stop accepting new work right away, then asks all
+/// running procs to terminate and waits up to `shutdown_timeout` for them to
+/// drain, escalating to SIGKILL for any that are still running once it
+/// elapses, so that a proc that ignores SIGTERM can't hang shutdown forever.
+async fn wait_for_shutdown_signal(
+    running_procs: SharedRunningProcs,
+    shutdown_timeout: Duration,
+    shutdown: watch::Sender<bool>,
+) {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("failed to install SIGTERM handler");
+    let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())
+        .expect("failed to install SIGINT handler");
+
+    tokio::select! {
+        _ = sigterm.recv() => eprintln!("received SIGTERM; shutting down"),
+        _ = sigint.recv() => eprintln!("received SIGINT; shutting down"),
     }
+
+    // Tell the HTTP/WS servers to stop accepting new work right away, before
+    // the drain below, so newly-arriving start requests aren't accepted only
+    // to be orphaned by the drain that follows.
+    let _ = shutdown.send(true);
+
+    if let Err(err) = running_procs.send_signal_all(Signum::SIGTERM) {
+        eprintln!("failed to signal running procs: {:?}", err);
+    }
+
+    if tokio::time::timeout(shutdown_timeout, running_procs.wait_running())
+        .await
+        .is_err()
+    {
+        eprintln!(
+            "{:?} shutdown grace period elapsed; sending SIGKILL to remaining procs",
+            shutdown_timeout
+        );
+        if let Err(err) = running_procs.send_signal_all(Signum::SIGKILL) {
+            eprintln!("failed to SIGKILL remaining procs: {:?}", err);
+        }
+        running_procs.wait_running().await;
+    }
+
+    running_procs.set_shutdown();
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
+    // Started before anything else, so its allocation timeline covers the
+    // whole run.  When not serving, it's dropped explicitly below, rather
+    // than relying on its destructor, since `std::process::exit` below skips
+    // drop glue and would otherwise lose the profile; when serving, it's
+    // dropped when `main` returns after shutdown.
+    #[cfg(feature = "dhat-heap")]
+    let profiler = dhat::Profiler::new_heap();
+
     let args = argv::parse();
 
     let running_procs = SharedRunningProcs::new();
     let input = if let Some(p) = args.input {
-        spec::load_file(&p).unwrap_or_else(|err| {
-            eprintln!("failed to load {}: {}", p, err);
-            std::process::exit(exitcode::OSFILE);
-        })
+        // Loading the spec file is blocking I/O; run it on the blocking
+        // thread pool rather than stalling the current-thread runtime.
+        let load_path = p.clone();
+        tokio::task::spawn_blocking(move || spec::load_file(&load_path))
+            .await
+            .expect("spec-load task panicked")
+            .unwrap_or_else(|err| {
+                eprintln!("failed to load {}: {}", p, err);
+                std::process::exit(exitcode::OSFILE);
+            })
     } else {
         spec::Input::new()
     };
@@ -51,15 +203,33 @@ async fn main() {
             .run_until(start_procs(input, running_procs.clone()))
             .await;
 
-        // Now run one or both servers.
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let shutdown_timeout = args.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT);
+
+        // Now run one or both servers.  When a shutdown signal arrives, the
+        // servers stop accepting new work right away while the running procs
+        // drain; once the drain is done we wait for the servers to finish
+        // in-flight requests before returning, rather than dropping them
+        // outright.
         local
-            .run_until(join(
-                maybe_run_http(args.serve, running_procs.clone()),
-                maybe_run_ws(args.connect, running_procs.clone()),
-            ))
+            .run_until(async {
+                let servers = join(
+                    maybe_run_http(args.serve, running_procs.clone(), shutdown_rx.clone()),
+                    maybe_run_ws(args.connect, running_procs.clone(), shutdown_rx.clone()),
+                );
+                tokio::pin!(servers);
+
+                tokio::select! {
+                    _ = &mut servers => {}
+                    _ = wait_for_shutdown_signal(running_procs.clone(), shutdown_timeout, shutdown_tx) => {
+                        servers.await;
+                    }
+                }
+            })
             .await;
     } else {
-        local
+        let exit_code_policy = args.exit_code_policy.unwrap_or_default();
+        let exit_code = local
             .run_until(async move {
                 // Start specs from the command line.
                 let tasks = start_procs(input, running_procs.clone()).await;
@@ -69,22 +239,41 @@ async fn main() {
                 }
                 // Collect results.
                 let result = collect_results(running_procs).await;
-                // Print them.
+                // Our own exit code is derived from the procs' exit codes
+                // per `exit_code_policy`, aggregated before the results are
+                // consumed below.
+                let exit_code = compute_exit_code(&result, exit_code_policy);
+                // Print them.  This is blocking I/O; run it on the blocking
+                // thread pool rather than stalling the current-thread
+                // runtime.
                 if let Some(path) = args.output {
-                    res::dump_file(&result, &path).unwrap_or_else(|err| {
-                        eprintln!("failed to write output {}: {}", path, err);
-                        std::process::exit(exitcode::OSFILE);
-                    });
+                    let dump_path = path.clone();
+                    tokio::task::spawn_blocking(move || res::dump_file(&result, &dump_path))
+                        .await
+                        .expect("result-dump task panicked")
+                        .unwrap_or_else(|err| {
+                            eprintln!("failed to write output {}: {}", path, err);
+                            std::process::exit(exitcode::OSFILE);
+                        });
                 } else {
-                    res::print(&result).unwrap_or_else(|err| {
-                        eprintln!("failed to print output: {}", err);
-                        std::process::exit(exitcode::OSFILE);
-                    });
+                    tokio::task::spawn_blocking(move || res::print(&result))
+                        .await
+                        .expect("print task panicked")
+                        .unwrap_or_else(|err| {
+                            eprintln!("failed to print output: {}", err);
+                            std::process::exit(exitcode::OSFILE);
+                        });
                     println!("");
                 }
+
+                exit_code
             })
             .await;
-        let ok = true; // FIXME: Determine if something went wrong.
-        std::process::exit(if ok { exitcode::OK } else { 1 });
+
+        // Flush the heap profile, if enabled, before exiting abruptly below.
+        #[cfg(feature = "dhat-heap")]
+        drop(profiler);
+
+        std::process::exit(exit_code);
     }
 }
@@ -3,7 +3,7 @@ use futures_util::future::FutureExt;
 use libc::pid_t;
 use log::*;
 use std::cell::RefCell;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::BTreeMap;
 use std::os::fd::RawFd;
 use std::rc::Rc;
 use std::sync::RwLock;
@@ -23,7 +23,7 @@ use crate::sig::{SignalReceiver, SignalWatcher, Signum};
 use crate::spec;
 use crate::spec::ProcId;
 use crate::state::State;
-use crate::sys::{execve, fork, kill, setsid, wait, WaitInfo};
+use crate::sys::{execve, fork, kill, seccomp_allow_only, set_rlimit, setsid, wait, PidFd, WaitInfo};
 
 //------------------------------------------------------------------------------
 
@@ -43,6 +43,24 @@ pub struct Proc {
     pub proc_stat: Option<ProcStat>,
     pub stop_time: Option<DateTime<Utc>>,
     pub elapsed: Option<Duration>,
+
+    /// If this proc was cancelled rather than allowed to run to completion,
+    /// the reason it was cancelled.
+    pub abort_reason: Option<String>,
+
+    /// Whether `send_signal` delivers to the whole process group rooted at
+    /// this proc, rather than just the leader pid, per
+    /// `spec::Proc::signal_group`.
+    pub signal_group: bool,
+
+    /// The rlimits configured for this proc (as rendered when they were set,
+    /// e.g. `"Cpu=30"`), if any, kept so that a later resource-limit-looking
+    /// termination signal can be correlated back to the limit that was
+    /// probably responsible.
+    pub configured_rlimits: Vec<String>,
+
+    /// Whether a seccomp syscall allowlist was installed for this proc.
+    pub seccomp_enabled: bool,
 }
 
 impl Proc {
@@ -51,6 +69,9 @@ impl Proc {
         start_time: DateTime<Utc>,
         start_instant: Instant,
         fd_handlers: FdHandlers,
+        signal_group: bool,
+        configured_rlimits: Vec<String>,
+        seccomp_enabled: bool,
     ) -> Self {
         Self {
             pid,
@@ -62,10 +83,40 @@ impl Proc {
             stop_time: None,
             start_instant,
             elapsed: None,
+            abort_reason: None,
+            signal_group,
+            configured_rlimits,
+            seccomp_enabled,
         }
     }
 
+    /// Cooperatively cancels this proc: records `reason` and signals it with
+    /// SIGTERM via `send_signal` (its process group or just the leader, per
+    /// `self.signal_group`), so that it has a chance to clean up before
+    /// exiting, rather than being killed outright.
+    pub fn abort(&mut self, reason: impl Into<String>) -> Result<(), Error> {
+        let reason = reason.into();
+        info!("proc {}: aborting: {}", self.pid, reason);
+        self.abort_reason = Some(reason);
+        self.send_signal(Signum::SIGTERM)
+    }
+
+    /// Signals this proc: the whole process group rooted at it if
+    /// `self.signal_group` is set, or just the leader pid otherwise.  Which
+    /// applies is controlled per-proc by `spec::Proc::signal_group`, since
+    /// some callers want to signal only the leader (e.g. a shell wrapper
+    /// that forwards signals to its own children) rather than every
+    /// grandchild.
     pub fn send_signal(&self, signum: Signum) -> Result<(), Error> {
+        if self.signal_group {
+            self.send_signal_group(signum)
+        } else {
+            self.send_signal_leader(signum)
+        }
+    }
+
+    /// Signals only the leader pid, not its process group.
+    pub fn send_signal_leader(&self, signum: Signum) -> Result<(), Error> {
         match kill(self.pid, signum) {
             Ok(()) => Ok(()),
             Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(Error::NoProc),
@@ -76,8 +127,32 @@ impl Proc {
         }
     }
 
+    /// Signals the whole process group rooted at this proc, not just the
+    /// leader, so that children it has spawned are signalled too.  This
+    /// relies on the proc having been placed in its own session (and hence
+    /// its own process group, with this pid as its group id) via setsid()
+    /// when it was started.
+    pub fn send_signal_group(&self, signum: Signum) -> Result<(), Error> {
+        // A negative pid tells kill() to signal the process group with that
+        // id, rather than a single process.
+        match kill(-self.pid, signum) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(Error::NoProc),
+            Err(err) => {
+                error!("kill: {}", err.kind());
+                Err(Error::from(err))
+            }
+        }
+    }
+
     pub fn get_state(&self) -> State {
-        if self.errors.len() > 0 {
+        if self.abort_reason.is_some() {
+            // Report an abort distinctly from ordinary termination and from
+            // pre-exec errors, so clients can tell a cooperatively
+            // cancelled proc from one that simply failed or ran to
+            // completion.
+            State::Aborted
+        } else if self.errors.len() > 0 {
             State::Error
         } else if self.wait_info.is_none() {
             State::Running
@@ -145,6 +220,7 @@ impl Proc {
             status,
             rusage,
             fds,
+            aborted: self.abort_reason.clone(),
         }
     }
 
@@ -194,6 +270,12 @@ pub enum Notification {
 
     /// Notification that a process has been deleted.
     Delete(ProcId),
+
+    /// Notification that more output is available on one of a process's
+    /// fds, for live tailing.  Carries the fd so a subscriber watching
+    /// several captured fds on the same proc (e.g. stdout and stderr) can
+    /// tell which one changed without polling both.
+    FdData(ProcId, RawFd),
 }
 
 type NotificationSender = broadcast::Sender<Notification>;
@@ -231,6 +313,36 @@ pub struct Procs {
 
     /// Soft shutdown request: shut down when next no processes remain.
     shutdown_on_idle: bool,
+
+    /// Proc IDs for which a start is currently in flight, so that concurrent
+    /// start requests for the same ID can be coalesced into the one already
+    /// running instead of racing to start a duplicate.
+    starting: BTreeMap<ProcId, Rc<StartingClaim>>,
+}
+
+/// Tracks a single in-flight `claim_start`, shared by every concurrent
+/// caller that coalesced onto it, so that they learn the actual outcome of
+/// the one start attempt rather than only that it finished.
+struct StartingClaim {
+    /// Wakes waiters once the claim is released.
+    notify: tokio::sync::Notify,
+    /// Set just before `notify` fires if the start attempt failed, so
+    /// waiters can propagate the same error instead of attempting their own
+    /// start against a proc ID that's already known to be broken.
+    error: RefCell<Option<String>>,
+}
+
+/// Outcome of a `claim_start` call.
+pub enum StartClaim {
+    /// The caller must start the proc itself, and must call `release_start`
+    /// with the outcome when done.
+    Start,
+    /// `proc_id` is already registered, whether by this caller or a
+    /// concurrent one; there's nothing more to do.
+    AlreadyRunning,
+    /// A concurrent start for `proc_id` already failed with this error;
+    /// propagate it rather than attempting another start.
+    Failed(String),
 }
 
 #[derive(Clone)]
@@ -244,9 +356,60 @@ impl SharedProcs {
             subs: sender,
             shutdown: watch::channel(false),
             shutdown_on_idle: false,
+            starting: BTreeMap::new(),
         })))
     }
 
+    /// Claims the right to start `proc_id`, for single-flight coalescing of
+    /// concurrent start requests for the same proc ID.  If `proc_id` is
+    /// already registered, returns `StartClaim::AlreadyRunning` so the
+    /// caller can skip starting it again and just use the already-registered
+    /// proc.  If a start for it is already in flight, waits for it to finish
+    /// and returns its actual outcome — `AlreadyRunning` if it succeeded, or
+    /// `Failed` with the same error if it didn't — rather than letting every
+    /// waiter race to start it independently.  Otherwise claims it and
+    /// returns `StartClaim::Start`, and the caller must call `release_start`
+    /// with the outcome when done.
+    pub async fn claim_start(&self, proc_id: &ProcId) -> StartClaim {
+        loop {
+            if self.get(proc_id).is_some() {
+                return StartClaim::AlreadyRunning;
+            }
+            let claim = self.0.borrow().starting.get(proc_id).cloned();
+            match claim {
+                Some(claim) => {
+                    claim.notify.notified().await;
+                    if let Some(error) = claim.error.borrow().clone() {
+                        return StartClaim::Failed(error);
+                    }
+                    // Otherwise the claim was released on success; loop
+                    // around and pick it up via the `get` check above.
+                }
+                None => {
+                    self.0.borrow_mut().starting.insert(
+                        proc_id.clone(),
+                        Rc::new(StartingClaim {
+                            notify: tokio::sync::Notify::new(),
+                            error: RefCell::new(None),
+                        }),
+                    );
+                    return StartClaim::Start;
+                }
+            }
+        }
+    }
+
+    /// Releases a claim taken with `claim_start`, waking any other callers
+    /// waiting to start the same proc ID.  `error`, if given, is the reason
+    /// the start attempt failed, and is delivered to every waiter as
+    /// `StartClaim::Failed` instead of letting them attempt their own start.
+    pub fn release_start(&self, proc_id: &ProcId, error: Option<String>) {
+        if let Some(claim) = self.0.borrow_mut().starting.remove(proc_id) {
+            *claim.error.borrow_mut() = error;
+            claim.notify.notify_waiters();
+        }
+    }
+
     // FIXME: Some of these methods are unused.
 
     pub fn insert(&self, proc_id: ProcId, proc: SharedProc) {
@@ -373,6 +536,14 @@ impl SharedProcs {
         }
     }
 
+    /// Notifies subscribers that more output is available on `proc_id`'s
+    /// `fd`, so that they can tail it live, by calling `get_fd_data(fd,
+    /// last_offset, None)`, instead of polling or waiting for the proc to
+    /// complete.
+    pub fn notify_fd_data(&self, proc_id: ProcId, fd: RawFd) {
+        self.notify(Notification::FdData(proc_id, fd));
+    }
+
     fn notify(&self, noti: Notification) {
         let s = self.0.borrow();
         if s.subs.receiver_count() > 0 {
@@ -380,6 +551,27 @@ impl SharedProcs {
         }
     }
 
+    /// Cooperatively cancels a proc, recording `reason` and, if it's still
+    /// running, signalling it to terminate.
+    pub fn abort(&self, proc_id: &ProcId, reason: impl Into<String>) -> Result<(), Error> {
+        let proc = self
+            .0
+            .borrow()
+            .procs
+            .get(proc_id)
+            .cloned()
+            .ok_or_else(|| Error::NoProcId(proc_id.clone()))?;
+        let mut proc = proc.borrow_mut();
+        if proc.get_state() == State::Running {
+            proc.abort(reason)
+        } else {
+            // Already not running; nothing to signal, but still record why
+            // cancellation was requested.
+            proc.abort_reason = Some(reason.into());
+            Ok(())
+        }
+    }
+
     /// Sends a signal to all running procs.
     pub fn send_signal_all(&self, signum: Signum) -> Result<(), Error> {
         let mut result = Ok(());
@@ -444,37 +636,131 @@ impl SharedProcs {
     }
 }
 
-async fn wait_for_proc(proc: SharedProc, mut sigchld_receiver: SignalReceiver) {
+/// Waits for `proc` to terminate, via `pidfd` if available, falling back to
+/// polling on SIGCHLD otherwise, and records its termination.
+async fn wait_for_proc(proc: SharedProc, sigchld_receiver: SignalReceiver) {
+    let pid = proc.borrow().pid;
+
+    match PidFd::open(pid) {
+        Ok(pidfd) => wait_for_proc_pidfd(proc, pidfd).await,
+        Err(err) => {
+            warn!(
+                "proc {}: pidfd_open failed ({}); falling back to SIGCHLD polling",
+                pid, err
+            );
+            wait_for_proc_sigchld(proc, sigchld_receiver).await
+        }
+    }
+}
+
+/// Waits for `proc` to terminate using a pidfd, which becomes readable once
+/// the process is a zombie.  This closes the race in
+/// `wait_for_proc_sigchld`, where the process could be reaped (and its
+/// `/proc/pid/stat` entry removed) between the SIGCHLD wakeup and the
+/// `ProcStat` read.
+async fn wait_for_proc_pidfd(proc: SharedProc, pidfd: PidFd) {
+    let pid = proc.borrow().pid;
+
+    // The process is guaranteed not to be reaped until we call wait()
+    // below, so it's safe to read its stat once the pidfd is readable.
+    if let Err(err) = pidfd.readable().await {
+        error!("proc {}: pidfd readiness wait failed: {}", pid, err);
+    }
+    let proc_stat = ProcStat::load_or_log(pid);
+
+    if let Some(wait_info) = wait(pid, false) {
+        info!("proc reaped: {}", pid);
+        record_termination(&proc, wait_info, proc_stat);
+    }
+}
+
+/// Waits for `proc` to terminate by polling a nonblocking wait() each time
+/// SIGCHLD is received.  Used when pidfds aren't available (e.g. an old
+/// kernel).  Races a reap against reading `/proc/pid/stat`, which may
+/// therefore be unavailable by the time it's read.
+async fn wait_for_proc_sigchld(proc: SharedProc, mut sigchld_receiver: SignalReceiver) {
     let pid = proc.borrow().pid;
 
     loop {
         // Wait until the process receives SIGCHLD.
         sigchld_receiver.signal().await;
 
-        // FIXME: HACK This won't do at all.  We need a way (pidfd?) to
-        // determine that this pid has terminated without calling wait(), so we
-        // can get its /proc/pid/stat first.
+        // FIXME: HACK This races a reap against reading /proc/pid/stat; see
+        // wait_for_proc_pidfd for the race-free version.
         let proc_stat = ProcStat::load_or_log(pid);
 
         // Check if this pid has terminated, with a nonblocking wait.
         if let Some(wait_info) = wait(pid, false) {
             info!("proc reaped: {}", pid);
-            // Take timestamps right away.
-            let stop_time = Utc::now();
-            let stop_instant = Instant::now();
-
-            // Process terminated; update its stuff.
-            let mut proc = proc.borrow_mut();
-            assert!(proc.wait_info.is_none());
-            proc.wait_info = Some(wait_info);
-            proc.proc_stat = proc_stat;
-            proc.stop_time = Some(stop_time);
-            proc.elapsed = Some(stop_instant.duration_since(proc.start_instant));
+            record_termination(&proc, wait_info, proc_stat);
             break;
         }
     }
 }
 
+/// Signals commonly raised by a resource limit or a seccomp filter killing
+/// the process, mapped to a human-readable explanation.  This is a best
+/// guess correlating the signal with whatever was configured for the proc,
+/// since the same signal can have other causes, but it gives operators a
+/// lead instead of just "killed by signal 24".
+fn likely_resource_cause(
+    signum: i32,
+    configured_rlimits: &[String],
+    seccomp_enabled: bool,
+) -> Option<String> {
+    let explanation = match signum {
+        libc::SIGXCPU if !configured_rlimits.is_empty() => {
+            format!(
+                "likely exceeded a configured CPU time rlimit ({})",
+                configured_rlimits.join(", ")
+            )
+        }
+        libc::SIGSEGV | libc::SIGBUS if !configured_rlimits.is_empty() => {
+            format!(
+                "possibly exceeded a configured memory/address-space rlimit ({})",
+                configured_rlimits.join(", ")
+            )
+        }
+        libc::SIGXFSZ if !configured_rlimits.is_empty() => {
+            format!(
+                "likely exceeded a configured file-size rlimit ({})",
+                configured_rlimits.join(", ")
+            )
+        }
+        libc::SIGSYS if seccomp_enabled => {
+            "likely killed by the seccomp syscall allowlist (disallowed syscall)".to_string()
+        }
+        _ => return None,
+    };
+    Some(format!("terminated by signal {}: {}", signum, explanation))
+}
+
+/// Records that `proc` has terminated with `wait_info`, with `proc_stat` as
+/// its last-known `/proc/pid/stat` snapshot.  If the termination signal
+/// matches a resource limit or seccomp filter configured for this proc, adds
+/// an error noting the likely cause, so it's diagnosable from `proc.errors`
+/// rather than showing up as a bare "killed by signal N".
+fn record_termination(proc: &SharedProc, wait_info: WaitInfo, proc_stat: Option<ProcStat>) {
+    // Take timestamps right away.
+    let stop_time = Utc::now();
+    let stop_instant = Instant::now();
+
+    let mut proc = proc.borrow_mut();
+    assert!(proc.wait_info.is_none());
+    let (_, status, _) = &wait_info;
+    if let Some(signum) = status.signal() {
+        if let Some(cause) =
+            likely_resource_cause(signum, &proc.configured_rlimits, proc.seccomp_enabled)
+        {
+            proc.errors.push(cause);
+        }
+    }
+    proc.wait_info = Some(wait_info);
+    proc.proc_stat = proc_stat;
+    proc.stop_time = Some(stop_time);
+    proc.elapsed = Some(stop_instant.duration_since(proc.start_instant));
+}
+
 /// Runs a recently-forked/execed process.
 async fn run_proc(proc: SharedProc, sigchld_receiver: SignalReceiver, error_pipe: ErrorPipe) {
     // FIXME: Error pipe should append directly to errors, so that they are
@@ -493,6 +779,49 @@ async fn run_proc(proc: SharedProc, sigchld_receiver: SignalReceiver, error_pipe
     _ = wait_task.await;
 }
 
+/// Grace period between escalating a timed-out proc from SIGTERM to
+/// SIGKILL, if it hasn't exited on its own.
+const TIMEOUT_KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Watches a proc for its configured wall-clock `timeout`.  If it's still
+/// running once the timeout elapses, sends SIGTERM; if it's still running
+/// after a further grace period, sends SIGKILL.
+async fn enforce_timeout(proc: SharedProc, timeout: Duration) {
+    // Whether the proc has actually been reaped, rather than `get_state()`,
+    // which as soon as we push the SIGTERM note below into `proc.errors`
+    // would report `State::Error` and make the proc look no longer running
+    // even though it's still alive and awaiting the SIGKILL escalation.
+    let is_terminated = |proc: &SharedProc| proc.borrow().wait_info.is_some();
+
+    tokio::time::sleep(timeout).await;
+    if is_terminated(&proc) {
+        return;
+    }
+    let pid = proc.borrow().pid;
+    warn!("proc {}: timed out after {:?}; sending SIGTERM", pid, timeout);
+    proc.borrow_mut()
+        .errors
+        .push(format!("timed out after {:?}; sent SIGTERM", timeout));
+    if let Err(err) = proc.borrow().send_signal(Signum::SIGTERM) {
+        error!("proc {}: failed to send SIGTERM: {:?}", pid, err);
+    }
+
+    tokio::time::sleep(TIMEOUT_KILL_GRACE).await;
+    if is_terminated(&proc) {
+        return;
+    }
+    warn!(
+        "proc {}: still running {:?} after SIGTERM; sending SIGKILL",
+        pid, TIMEOUT_KILL_GRACE
+    );
+    proc.borrow_mut()
+        .errors
+        .push("did not exit after SIGTERM; sent SIGKILL".to_string());
+    if let Err(err) = proc.borrow().send_signal(Signum::SIGKILL) {
+        error!("proc {}: failed to send SIGKILL: {:?}", pid, err);
+    }
+}
+
 //------------------------------------------------------------------------------
 
 /// If some, `start_procs()` only starts a process with exactly this executable.
@@ -515,34 +844,53 @@ fn get_exe(spec: &spec::Proc) -> &str {
 }
 
 /// Starts zero or more new processes.  `input` maps new proc IDs to
-/// corresponding process specs.  All proc IDs must be unused.
+/// corresponding process specs.  A proc ID already registered is skipped.  A
+/// proc ID already being started by a concurrent call to this function is
+/// neither raced nor rejected as a duplicate: this waits for that start to
+/// finish, then skips the ID, logging the other start's error if it failed.
 ///
 /// Because this function starts tasks with `spawn_local`, it must be run within
 /// a `LocalSet`.
-pub fn start_procs(
+pub async fn start_procs(
     specs: &spec::Procs,
     procs: &SharedProcs,
 ) -> Result<Vec<tokio::task::JoinHandle<()>>, spec::Error> {
-    // First check that proc IDs aren't already in use.
-    let old_proc_ids = procs.get_proc_ids::<HashSet<_>>();
-    let dup_proc_ids = specs
-        .keys()
-        .filter(|&p| old_proc_ids.contains(p))
-        .map(|p| p.to_string())
-        .collect::<Vec<_>>();
-    for proc_id in dup_proc_ids.into_iter() {
-        return Err(spec::Error::DuplicateProcId(proc_id));
-    }
-
     spec::validate_procs_fds(specs)?;
 
+    // Claim each proc ID, skipping any that are already registered.  If a
+    // concurrent start for the same ID is already in flight, wait for its
+    // outcome instead of racing to start a duplicate: on success there's
+    // nothing more to do, and on failure log the same error rather than
+    // attempting another start against a spec that's already known bad.
+    let mut claimed = Vec::new();
+    for proc_id in specs.keys() {
+        match procs.claim_start(proc_id).await {
+            StartClaim::Start => claimed.push(proc_id.clone()),
+            StartClaim::AlreadyRunning => {}
+            StartClaim::Failed(err) => {
+                error!(
+                    "proc {}: concurrent start already failed: {}",
+                    proc_id, err
+                );
+            }
+        }
+    }
+
     let (sigchld_watcher, sigchld_receiver) =
         SignalWatcher::new(tokio::signal::unix::SignalKind::child());
     let _sigchld_task = tokio::spawn(sigchld_watcher.watch());
     let mut tasks = Vec::new();
 
-    for (proc_id, spec) in specs.into_iter() {
-        let env = environ::build(std::env::vars(), &spec.env);
+    for (proc_id, spec) in specs.into_iter().filter(|(p, _)| claimed.contains(p)) {
+        let env = match environ::build(std::env::vars(), &spec.env) {
+            Ok(env) => env,
+            Err(err) => {
+                let msg = format!("failed to build environment: {}", err);
+                error!("proc {}: {}", proc_id, msg);
+                procs.release_start(proc_id, Some(msg));
+                continue;
+            }
+        };
         let exe = get_exe(&spec);
 
         let error_pipe = ErrorPipe::new().unwrap_or_else(|err| {
@@ -589,6 +937,26 @@ pub fn start_procs(
                     ok_to_exec = false;
                 }
 
+                // Apply resource limits, if configured.
+                for (resource, limit) in spec.rlimits.iter() {
+                    if let Err(err) = set_rlimit(resource, *limit) {
+                        error_writer
+                            .try_write(format!("failed to set rlimit {}: {}", resource, err));
+                        ok_to_exec = false;
+                    }
+                }
+
+                // Install a seccomp syscall allowlist, if configured.  This
+                // happens last, immediately before execve, since it also
+                // restricts the syscalls available to the setup above.
+                if let Some(allowed_syscalls) = &spec.seccomp {
+                    if let Err(err) = seccomp_allow_only(allowed_syscalls) {
+                        error_writer
+                            .try_write(format!("failed to install seccomp filter: {}", err));
+                        ok_to_exec = false;
+                    }
+                }
+
                 if ok_to_exec {
                     // execve() only returns with an error; on success, the program is
                     // replaced.
@@ -609,25 +977,58 @@ pub fn start_procs(
                 let mut fd_errs: Vec<String> = Vec::new();
                 let _fd_handler_tasks = fd_handlers
                     .iter()
-                    .filter_map(|(ref fd, ref fd_handler)| match fd_handler.in_parent() {
-                        Ok(task) => Some(task),
-                        Err(err) => {
-                            fd_errs.push(format!("failed to set up fd {}: {}", fd, err));
-                            None
+                    .filter_map(|(fd, fd_handler)| {
+                        // Notify subscribers directly from the fd handler's
+                        // own append path, rather than having a separate
+                        // task poll for new output, so live-tailing
+                        // subscribers learn of new data as soon as it
+                        // arrives instead of up to `OUTPUT_POLL_INTERVAL`
+                        // later.
+                        let procs = procs.clone();
+                        let proc_id = proc_id.clone();
+                        let fd = *fd;
+                        match fd_handler.in_parent(move || procs.notify_fd_data(proc_id.clone(), fd)) {
+                            Ok(task) => Some(task),
+                            Err(err) => {
+                                fd_errs.push(format!("failed to set up fd {}: {}", fd, err));
+                                None
+                            }
                         }
                     })
                     .collect::<Vec<_>>();
 
                 // Construct the record of this running proc.
-                let mut proc = Proc::new(child_pid, start_time, start_instant, fd_handlers);
+                let configured_rlimits = spec
+                    .rlimits
+                    .iter()
+                    .map(|(resource, limit)| format!("{}={}", resource, limit))
+                    .collect();
+                let mut proc = Proc::new(
+                    child_pid,
+                    start_time,
+                    start_instant,
+                    fd_handlers,
+                    spec.signal_group,
+                    configured_rlimits,
+                    spec.seccomp.is_some(),
+                );
 
                 // Attach any fd errors.
                 proc.errors.append(&mut fd_errs);
                 drop(fd_errs);
 
-                // Register the new proc.
+                // Register the new proc, releasing its start claim so that
+                // any concurrent caller waiting on the same ID wakes and
+                // finds it registered.
                 let proc = Rc::new(RefCell::new(proc));
                 procs.insert(proc_id.clone(), proc.clone());
+                procs.release_start(&proc_id, None);
+
+                // If a wall-clock timeout is configured, watch for it and
+                // escalate signals if the proc doesn't exit in time.
+                if let Some(timeout) = spec.timeout {
+                    tokio::task::spawn_local(enforce_timeout(proc.clone(), timeout));
+                }
 
                 // Build the task that awaits the process.
                 let fut = run_proc(proc, sigchld_receiver.clone(), error_pipe);
@@ -1,9 +1,12 @@
 use futures::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
 use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
+use tokio::sync::watch;
 use tokio::time::sleep;
 use tokio_tungstenite::tungstenite::protocol::Message;
 use tokio_tungstenite::{
@@ -12,8 +15,9 @@ use tokio_tungstenite::{
 use url::Url;
 
 use crate::procinfo::ProcessInfo;
-use crate::procs::{ProcNotification, ProcNotificationReceiver, SharedProcs};
+use crate::procs::{Notification, NotificationSub, SharedProcs};
 use crate::proto;
+use crate::spec::ProcId;
 
 // FIXME: Replace `eprintln` for errors with something more appropriate.
 
@@ -32,6 +36,38 @@ pub struct Connection {
     conn: proto::ConnectionInfo,
     /// Information about this process running procstar.
     proc: ProcessInfo,
+    /// Wire format used to encode and decode protocol messages.
+    format: WireFormat,
+    /// TLS configuration used when connecting over `wss://`.
+    tls: TlsConfig,
+    /// Initial wait time before a reconnection attempt.
+    reconnect_interval_start: Duration,
+    /// Maximum wait time between reconnection attempts.
+    reconnect_interval_max: Duration,
+}
+
+/// TLS configuration for a connection's websocket transport.  Defaults are
+/// secure: full certificate/hostname verification against the platform
+/// trust store, with no custom CA or client certificate.
+#[derive(Clone, Debug, Default)]
+pub struct TlsConfig {
+    /// Path to an additional CA certificate (PEM) to trust, for servers
+    /// whose certificate isn't signed by a CA in the platform trust store.
+    pub ca_cert_path: Option<PathBuf>,
+    /// Path to a client certificate (PEM), for mutual TLS.  Requires
+    /// `client_key_path`.
+    pub client_cert_path: Option<PathBuf>,
+    /// Path to the private key (PEM) for `client_cert_path`.
+    pub client_key_path: Option<PathBuf>,
+    /// Minimum TLS protocol version to accept.  Defaults to TLS 1.2.
+    pub min_protocol_version: Option<native_tls::Protocol>,
+    /// Disables server certificate verification.  Opt-in only (defaults to
+    /// `false`); accepts invalid or self-signed certificates and should
+    /// only be used for testing.
+    pub danger_accept_invalid_certs: bool,
+    /// Disables server hostname verification.  Opt-in only (defaults to
+    /// `false`); should only be used for testing.
+    pub danger_accept_invalid_hostnames: bool,
 }
 
 impl Connection {
@@ -41,20 +77,115 @@ impl Connection {
         let group_id = group_id.map_or(proto::DEFAULT_GROUP.to_string(), |n| n.to_string());
         let conn = proto::ConnectionInfo { conn_id, group_id };
         let proc = ProcessInfo::new_self();
-        Connection { url, conn, proc }
+        Connection {
+            url,
+            conn,
+            proc,
+            format: WireFormat::Json,
+            tls: TlsConfig::default(),
+            reconnect_interval_start: RECONNECT_INTERVAL_START,
+            reconnect_interval_max: RECONNECT_INTERVAL_MAX,
+        }
+    }
+
+    /// Sets the wire format used to encode and decode protocol messages.
+    /// Defaults to `WireFormat::Json`.
+    pub fn with_format(mut self, format: WireFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets a custom CA certificate (PEM) to trust, in addition to the
+    /// platform's default trust store.
+    pub fn with_ca_cert_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.tls.ca_cert_path = Some(path.into());
+        self
+    }
+
+    /// Sets a client certificate and private key (PEM) to present for
+    /// mutual TLS.
+    pub fn with_client_cert(mut self, cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        self.tls.client_cert_path = Some(cert_path.into());
+        self.tls.client_key_path = Some(key_path.into());
+        self
+    }
+
+    /// Sets the minimum TLS protocol version to accept.  Defaults to
+    /// TLS 1.2.
+    pub fn with_min_tls_version(mut self, version: native_tls::Protocol) -> Self {
+        self.tls.min_protocol_version = Some(version);
+        self
+    }
+
+    /// Sets whether to verify the server's TLS certificate and hostname.
+    /// Defaults to `true`; disabling this accepts invalid or self-signed
+    /// certificates and should only be used for testing.
+    pub fn with_tls_verify(mut self, tls_verify: bool) -> Self {
+        self.tls.danger_accept_invalid_certs = !tls_verify;
+        self.tls.danger_accept_invalid_hostnames = !tls_verify;
+        self
+    }
+
+    /// Sets the initial wait time before a reconnection attempt.  Defaults
+    /// to 100 ms.
+    pub fn with_reconnect_interval(mut self, interval: Duration) -> Self {
+        self.reconnect_interval_start = interval;
+        self
+    }
+
+    /// Sets the maximum wait time between reconnection attempts, capping
+    /// the exponential backoff.  Defaults to 30 s.
+    pub fn with_max_reconnect_interval(mut self, interval: Duration) -> Self {
+        self.reconnect_interval_max = interval;
+        self
+    }
+}
+
+/// Wire format used to encode and decode protocol messages sent over the
+/// websocket, as an alternative to the default JSON encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireFormat {
+    /// Messages are encoded as JSON, each in its own binary websocket frame.
+    Json,
+    /// Messages are encoded as MessagePack, which is more compact and
+    /// faster to (de)serialize than JSON.
+    MessagePack,
+}
+
+impl WireFormat {
+    fn encode<T: serde::Serialize>(&self, val: &T) -> Result<Vec<u8>, proto::Error> {
+        match self {
+            WireFormat::Json => Ok(serde_json::to_vec(val)?),
+            WireFormat::MessagePack => rmp_serde::to_vec_named(val).map_err(|err| {
+                proto::Error::WrongMessageType(format!("msgpack encode error: {}", err))
+            }),
+        }
+    }
+
+    fn decode<T: serde::de::DeserializeOwned>(&self, data: &[u8]) -> Result<T, proto::Error> {
+        match self {
+            WireFormat::Json => Ok(serde_json::from_slice(data)?),
+            WireFormat::MessagePack => rmp_serde::from_slice(data).map_err(|err| {
+                proto::Error::WrongMessageType(format!("msgpack decode error: {}", err))
+            }),
+        }
     }
 }
 
 /// Handler for incoming messages on a websocket client connection.
-async fn handle(procs: SharedProcs, msg: Message) -> Result<Option<Message>, proto::Error> {
+async fn handle(
+    procs: SharedProcs,
+    msg: Message,
+    format: WireFormat,
+) -> Result<Option<Message>, proto::Error> {
     match msg {
-        Message::Binary(json) => {
-            let msg = serde_json::from_slice::<proto::IncomingMessage>(&json)?;
+        Message::Binary(data) => {
+            let msg = format.decode::<proto::IncomingMessage>(&data)?;
             eprintln!("msg: {:?}", msg);
             if let Some(rsp) = proto::handle_incoming(procs, msg).await {
                 eprintln!("rsp: {:?}", rsp);
-                let json = serde_json::to_vec(&rsp)?;
-                Ok(Some(Message::Binary(json)))
+                let data = format.encode(&rsp)?;
+                Ok(Some(Message::Binary(data)))
             } else {
                 Ok(None)
             }
@@ -68,25 +199,86 @@ async fn handle(procs: SharedProcs, msg: Message) -> Result<Option<Message>, pro
     }
 }
 
-async fn send(sender: &mut SocketSender, msg: proto::OutgoingMessage) -> Result<(), proto::Error> {
-    let json = serde_json::to_vec(&msg)?;
-    sender.send(Message::Binary(json)).await.unwrap();
+async fn send(
+    sender: &mut SocketSender,
+    msg: proto::OutgoingMessage,
+    format: WireFormat,
+) -> Result<(), proto::Error> {
+    let data = format.encode(&msg)?;
+    sender
+        .send(Message::Binary(data))
+        .await
+        .map_err(|err| proto::Error::WrongMessageType(format!("send error: {}", err)))?;
     Ok(())
 }
 
+/// Builds the `native_tls` connector for `tls`, applying the configured CA
+/// bundle, client certificate, minimum protocol version, and danger flags.
+/// Returns an error rather than panicking, so that a misconfigured
+/// `TlsConfig` (e.g. an unreadable cert file) surfaces to the retry path in
+/// `run()` instead of crashing the task.
+fn build_tls_connector(tls: &TlsConfig) -> Result<native_tls::TlsConnector, proto::Error> {
+    let mut builder = native_tls::TlsConnector::builder();
+
+    if let Some(ca_cert_path) = &tls.ca_cert_path {
+        let pem = std::fs::read(ca_cert_path).map_err(|err| {
+            proto::Error::WrongMessageType(format!(
+                "failed to read CA cert {}: {}",
+                ca_cert_path.display(),
+                err
+            ))
+        })?;
+        let cert = native_tls::Certificate::from_pem(&pem)
+            .map_err(|err| proto::Error::WrongMessageType(format!("invalid CA cert: {}", err)))?;
+        builder.add_root_certificate(cert);
+    }
+
+    if let (Some(cert_path), Some(key_path)) = (&tls.client_cert_path, &tls.client_key_path) {
+        let cert_pem = std::fs::read(cert_path).map_err(|err| {
+            proto::Error::WrongMessageType(format!(
+                "failed to read client cert {}: {}",
+                cert_path.display(),
+                err
+            ))
+        })?;
+        let key_pem = std::fs::read(key_path).map_err(|err| {
+            proto::Error::WrongMessageType(format!(
+                "failed to read client key {}: {}",
+                key_path.display(),
+                err
+            ))
+        })?;
+        let identity = native_tls::Identity::from_pkcs8(&cert_pem, &key_pem).map_err(|err| {
+            proto::Error::WrongMessageType(format!("invalid client certificate/key: {}", err))
+        })?;
+        builder.identity(identity);
+    }
+
+    builder.min_protocol_version(Some(
+        tls.min_protocol_version.unwrap_or(native_tls::Protocol::Tlsv12),
+    ));
+    if tls.danger_accept_invalid_certs {
+        builder.danger_accept_invalid_certs(true);
+    }
+    if tls.danger_accept_invalid_hostnames {
+        builder.danger_accept_invalid_hostnames(true);
+    }
+
+    builder
+        .build()
+        .map_err(|err| proto::Error::WrongMessageType(format!("TLS configuration error: {}", err)))
+}
+
 async fn connect(
     connection: &mut Connection,
 ) -> Result<(SocketSender, SocketReceiver), proto::Error> {
     eprintln!("connecting to {}", connection.url);
 
-    let mut builder = native_tls::TlsConnector::builder();
-    builder.danger_accept_invalid_certs(true);
-    builder.danger_accept_invalid_hostnames(true);
-    builder.min_protocol_version(Some(native_tls::Protocol::Tlsv12));
-    let connector = Connector::NativeTls(builder.build().unwrap()); // FIXME: Unwrap.
+    let connector = Connector::NativeTls(build_tls_connector(&connection.tls)?);
 
-    let (ws_stream, _) =
-        connect_async_tls_with_config(&connection.url, None, false, Some(connector)).await.unwrap();
+    let (ws_stream, _) = connect_async_tls_with_config(&connection.url, None, false, Some(connector))
+        .await
+        .map_err(|err| proto::Error::WrongMessageType(format!("connect error: {}", err)))?;
     eprintln!("connected");
     let (mut sender, receiver) = ws_stream.split();
 
@@ -95,18 +287,100 @@ async fn connect(
         conn: connection.conn.clone(),
         proc: connection.proc.clone(),
     };
-    send(&mut sender, register).await?;
+    send(&mut sender, register, connection.format).await?;
 
     Ok((sender, receiver))
 }
 
+/// Notifications awaiting delivery, accumulated while disconnected and
+/// replayed, in order, once a connection is (re-)established.  These are
+/// kept as the raw notifications, not pre-built outgoing messages, so that
+/// `notification_to_message` can re-resolve current proc state at flush
+/// time rather than reporting a stale snapshot for a proc that was deleted
+/// in the interim.
+type PendingNotifications = Rc<RefCell<VecDeque<Notification>>>;
+
+/// Maximum number of notifications to hold while disconnected, so that a
+/// long outage with many proc completions can't grow the queue without
+/// bound.
+const PENDING_NOTIFICATIONS_CAP: usize = 1024;
+
+/// Returns the proc ID that `noti` concerns.
+fn proc_id_of(noti: &Notification) -> &ProcId {
+    match noti {
+        Notification::Start(proc_id)
+        | Notification::NotRunning(proc_id)
+        | Notification::Delete(proc_id)
+        | Notification::FdData(proc_id, _) => proc_id,
+    }
+}
+
+/// Returns a coalescing class for `noti`, or `None` if it must never be
+/// coalesced away.  `Start` and `NotRunning` for the same proc ID both
+/// resolve to the same re-fetched `ProcResult` at flush time, so only the
+/// most recent of the two needs to be kept; a `Delete`, however, must never
+/// cause an as-yet-unflushed `Start`/`NotRunning` to be dropped, or the
+/// server would learn a proc was deleted without ever learning its result.
+fn coalesce_class(noti: &Notification) -> Option<u8> {
+    match noti {
+        Notification::Start(_) | Notification::NotRunning(_) => Some(0),
+        Notification::Delete(_) | Notification::FdData(..) => None,
+    }
+}
+
+/// Queues `noti` for later delivery, coalescing with any already-pending
+/// notification of the same coalescing class for the same proc ID (see
+/// `coalesce_class`), and enforcing `PENDING_NOTIFICATIONS_CAP` by dropping
+/// the oldest entry if still over the cap afterward.
+fn enqueue_notification(pending: &PendingNotifications, noti: Notification) {
+    let mut pending = pending.borrow_mut();
+    if let Some(class) = coalesce_class(&noti) {
+        let proc_id = proc_id_of(&noti).clone();
+        pending.retain(|existing| {
+            !(*proc_id_of(existing) == proc_id && coalesce_class(existing) == Some(class))
+        });
+    }
+    pending.push_back(noti);
+    while pending.len() > PENDING_NOTIFICATIONS_CAP {
+        let dropped = pending.pop_front();
+        eprintln!("pending notification queue full; dropped {:?}", dropped);
+    }
+}
+
+/// Sends as many pending notifications as possible over `sender`, in order,
+/// resolving each to an outgoing message against current proc state right
+/// before sending it, and stopping at (and leaving queued) the first one
+/// that fails to send.
+async fn flush_pending(
+    procs: &SharedProcs,
+    sender: &mut SocketSender,
+    pending: &PendingNotifications,
+    format: WireFormat,
+) -> Result<(), proto::Error> {
+    loop {
+        // Peek at the next pending notification without removing it yet,
+        // and without holding the borrow across the `.await` below: if
+        // sending fails, it must still be there to retry on the next
+        // reconnect, not silently lost.
+        let noti = match pending.borrow().front().cloned() {
+            Some(noti) => noti,
+            None => return Ok(()),
+        };
+        if let Some(msg) = notification_to_message(procs, noti) {
+            send(sender, msg, format).await?;
+        }
+        // Sent (or resolved to nothing to send); now safe to remove.
+        pending.borrow_mut().pop_front();
+    }
+}
+
 /// Constructs an outgoing message corresponding to a notification message.
 fn notification_to_message(
     procs: &SharedProcs,
-    noti: ProcNotification,
+    noti: Notification,
 ) -> Option<proto::OutgoingMessage> {
     match noti {
-        ProcNotification::Start(proc_id) | ProcNotification::Complete(proc_id) => {
+        Notification::Start(proc_id) | Notification::NotRunning(proc_id) => {
             // Look up the proc.
             if let Some(proc) = procs.get(&proc_id) {
                 // Got it.  Send its result.
@@ -119,41 +393,46 @@ fn notification_to_message(
             }
         }
 
-        ProcNotification::Delete(proc_id) => Some(proto::OutgoingMessage::ProcDelete { proc_id }),
+        Notification::Delete(proc_id) => Some(proto::OutgoingMessage::ProcDelete { proc_id }),
+
+        // Live-tailing notifications aren't delivered as queued,
+        // re-resolved messages; subscribers call `get_fd_data` directly in
+        // response to these.
+        Notification::FdData(..) => None,
     }
 }
 
-/// Background task that receives notification messages through `noti_sender`,
-/// converts them to outgoing messages, and sends them via `sender`.
+/// Background task that receives notification messages through `noti_receiver`
+/// and queues them in `pending` for delivery, converting each to an
+/// outgoing message only once it's actually sent.  While disconnected,
+/// notifications accumulate in `pending` instead of being dropped, so that
+/// they can be replayed once reconnected.
 async fn send_notifications(
     procs: SharedProcs,
-    mut noti_receiver: ProcNotificationReceiver,
+    mut noti_receiver: NotificationSub,
     sender: Rc<RefCell<Option<SocketSender>>>,
+    pending: PendingNotifications,
+    format: WireFormat,
 ) {
     loop {
         // Wait for a notification to arrive on the channel.
         match noti_receiver.recv().await {
             Some(noti) => {
-                // Borrow the websocket sender.
+                enqueue_notification(&pending, noti);
+
+                // Borrow the websocket sender and flush whatever is pending,
+                // in order, including this notification.
                 if let Some(sender) = sender.borrow_mut().as_mut() {
-                    // Generate the outgoing message corresponding to the
-                    // notification.
-                    if let Some(msg) = notification_to_message(&procs, noti) {
-                        // Send the outgoing message.
-                        if let Err(err) = send(sender, msg).await {
-                            eprintln!("msg send error: {:?}", err);
-                            // Close the websocket.
-                            if let Err(err) = sender.close().await {
-                                eprintln!("websocket close error: {:?}", err);
-                            }
+                    if let Err(err) = flush_pending(&procs, sender, &pending, format).await {
+                        eprintln!("msg send error: {:?}", err);
+                        // Close the websocket.
+                        if let Err(err) = sender.close().await {
+                            eprintln!("websocket close error: {:?}", err);
                         }
-                    } else {
-                        // No outgoing message corresponding to this
-                        // notification.
                     }
                 } else {
                     // No current websocket sender; we are not currently
-                    // connected.  Drop this notification.
+                    // connected.  Leave it queued for replay on reconnect.
                 }
             }
             // End of channel.
@@ -167,11 +446,49 @@ const RECONNECT_INTERVAL_START: Duration = Duration::from_millis(100);
 const RECONNECT_INTERVAL_MULT: f64 = 2.;
 const RECONNECT_INTERVAL_MAX: Duration = Duration::from_secs(30);
 
-pub async fn run(mut connection: Connection, procs: SharedProcs) -> Result<(), proto::Error> {
+/// Maximum jitter, as a fraction of the backoff interval, applied to each
+/// reconnection wait, to avoid many agents reconnecting in lockstep.
+const RECONNECT_JITTER_FRACTION: f64 = 0.2;
+
+/// How long a connection must stay up before we consider it stable and
+/// reset the backoff interval on its next disconnect, rather than
+/// continuing to escalate.  Without this, a connection that connects
+/// successfully but immediately drops (e.g. the server rejects us right
+/// after the handshake) would retry at the fastest interval forever.
+const RECONNECT_STABLE_AFTER: Duration = Duration::from_secs(30);
+
+/// Applies random jitter of up to `RECONNECT_JITTER_FRACTION` to `interval`.
+fn jittered(interval: Duration) -> Duration {
+    use rand::Rng;
+    let factor = rand::thread_rng().gen_range(-RECONNECT_JITTER_FRACTION..=RECONNECT_JITTER_FRACTION);
+    interval.mul_f64(1. + factor)
+}
+
+/// Interval between client-initiated heartbeat pings.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// How long to wait without hearing from the server (a pong or any other
+/// message) before considering the connection dead.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Runs the websocket client: connects, handles incoming messages and sends
+/// outgoing notifications, and reconnects with backoff on disconnect, until
+/// `shutdown` is set, at which point it stops reconnecting and returns
+/// rather than retrying forever.
+pub async fn run(
+    mut connection: Connection,
+    procs: SharedProcs,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), proto::Error> {
+    let format = connection.format;
+
     // Create a shared websocket sender, which is shared between the
     // notification sender and the main message loop.
     let sender: Rc<RefCell<Option<SocketSender>>> = Rc::new(RefCell::new(None));
 
+    // Notifications generated while disconnected accumulate here, and are
+    // replayed, in order, once the connection is (re-)established.
+    let pending: PendingNotifications = Rc::new(RefCell::new(VecDeque::new()));
+
     // Subscribe to receive asynchronous notifications, such as when a process
     // completes.
     let noti_receiver = procs.subscribe();
@@ -181,63 +498,143 @@ pub async fn run(mut connection: Connection, procs: SharedProcs) -> Result<(), p
         procs.clone(),
         noti_receiver,
         sender.clone(),
+        pending.clone(),
+        format,
     ));
 
-    let mut interval = RECONNECT_INTERVAL_START;
+    let mut interval = connection.reconnect_interval_start;
     loop {
-        // (Re)connect to the service.
-        let (new_sender, mut receiver) = match connect(&mut connection).await {
-            Ok(pair) => pair,
-            Err(err) => {
-                eprintln!("error: {:?}", err);
-                // Reconnect, after a moment.
-                // FIXME: Is this the right policy?
-                sleep(interval).await;
-                interval = interval.mul_f64(RECONNECT_INTERVAL_MULT);
-                if RECONNECT_INTERVAL_MAX < interval {
-                    interval = RECONNECT_INTERVAL_MAX;
+        if *shutdown.borrow() {
+            return Ok(());
+        }
+
+        // (Re)connect to the service, unless a shutdown arrives first.
+        let (new_sender, mut receiver) = tokio::select! {
+            result = connect(&mut connection) => match result {
+                Ok(pair) => pair,
+                Err(err) => {
+                    eprintln!("error: {:?}", err);
+                    // Reconnect, after a moment, with exponential backoff and
+                    // jitter so that many agents don't reconnect in lockstep,
+                    // unless a shutdown arrives during the wait.
+                    tokio::select! {
+                        _ = sleep(jittered(interval)) => {}
+                        _ = shutdown.changed() => return Ok(()),
+                    }
+                    interval = interval.mul_f64(RECONNECT_INTERVAL_MULT);
+                    if connection.reconnect_interval_max < interval {
+                        interval = connection.reconnect_interval_max;
+                    }
+                    continue;
                 }
-                std::process::exit(1);
-                // continue;
-            }
+            },
+            _ = shutdown.changed() => return Ok(()),
         };
-        // Connected.  There's now a websocket sender available.
+        // Connected.  There's now a websocket sender available.  Don't
+        // reset the backoff yet: a connection that drops again right away
+        // should keep escalating rather than spinning at the fastest
+        // interval.  We only reset once the connection has proven stable
+        // for `RECONNECT_STABLE_AFTER`, below.
+        let connected_at = Instant::now();
         sender.replace(Some(new_sender));
 
-        loop {
-            match receiver.next().await {
-                Some(Ok(msg)) => match handle(procs.clone(), msg).await {
-                    Ok(Some(rsp))
-                        // Handling the incoming message produced a response;
-                        // send it back.
-                        => if let Err(err) = sender.borrow_mut().as_mut().unwrap().send(rsp).await {
-                            eprintln!("msg send error: {:?}", err);
-                            break;
-                        },
-                    Ok(None)
-                        // Handling the message produced no response.
-                        => {},
-                    Err(err)
-                        // Error while handling the message.
-                        => {
-                            eprintln!("msg handle error: {:?}", err);
-                            break;
-                        },
-                },
-                Some(Err(err)) => {
-                    eprintln!("msg receive error: {:?}", err);
-                    break;
+        // Replay any notifications that piled up while we were disconnected.
+        if let Some(s) = sender.borrow_mut().as_mut() {
+            if let Err(err) = flush_pending(&procs, s, &pending, format).await {
+                eprintln!("msg send error: {:?}", err);
+            }
+        }
+
+        // Track the last time we heard from the server, so we can detect a
+        // dead connection that never responds to our pings.
+        let mut last_heard = Instant::now();
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+        'connected: loop {
+            tokio::select! {
+                // A shutdown arrived; stop reconnecting once we close this
+                // connection, instead of looping back to reconnect.
+                _ = shutdown.changed() => {
+                    if *shutdown.borrow() {
+                        eprintln!("shutdown requested; closing websocket connection");
+                        break 'connected;
+                    }
                 }
-                None => {
-                    eprintln!("msg stream end");
-                    break;
+
+                // Time to send a client-initiated heartbeat ping, unless the
+                // server has gone quiet for too long.
+                _ = heartbeat.tick() => {
+                    if HEARTBEAT_TIMEOUT < last_heard.elapsed() {
+                        eprintln!("heartbeat timeout: no pong from server");
+                        break 'connected;
+                    }
+                    if let Err(err) = sender.borrow_mut().as_mut().unwrap().send(Message::Ping(Vec::new())).await {
+                        eprintln!("ping send error: {:?}", err);
+                        break 'connected;
+                    }
                 }
+
+                next = receiver.next() => match next {
+                    Some(Ok(Message::Pong(_))) => {
+                        last_heard = Instant::now();
+                    }
+                    Some(Ok(msg)) => {
+                        last_heard = Instant::now();
+                        match handle(procs.clone(), msg, format).await {
+                            Ok(Some(rsp))
+                                // Handling the incoming message produced a response;
+                                // send it back.
+                                => if let Err(err) = sender.borrow_mut().as_mut().unwrap().send(rsp).await {
+                                    eprintln!("msg send error: {:?}", err);
+                                    break 'connected;
+                                },
+                            Ok(None)
+                                // Handling the message produced no response.
+                                => {},
+                            Err(err)
+                                // Error while handling the message.
+                                => {
+                                    eprintln!("msg handle error: {:?}", err);
+                                    break 'connected;
+                                },
+                        }
+                    }
+                    Some(Err(err)) => {
+                        eprintln!("msg receive error: {:?}", err);
+                        break 'connected;
+                    }
+                    None => {
+                        eprintln!("msg stream end");
+                        break 'connected;
+                    }
+                },
             }
         }
 
         // The connection is closed.  No sender is available.
         sender.replace(None);
 
+        // If the connection stayed up long enough to be considered stable,
+        // reset the backoff; otherwise keep escalating, since reconnecting
+        // immediately at full speed to a server that's rejecting us would
+        // just spin.
+        if RECONNECT_STABLE_AFTER <= connected_at.elapsed() {
+            interval = connection.reconnect_interval_start;
+        }
+
+        // Back off before reconnecting, unless shutting down.
+        if *shutdown.borrow() {
+            return Ok(());
+        }
+        tokio::select! {
+            _ = sleep(jittered(interval)) => {}
+            _ = shutdown.changed() => return Ok(()),
+        }
+        interval = interval.mul_f64(RECONNECT_INTERVAL_MULT);
+        if connection.reconnect_interval_max < interval {
+            interval = connection.reconnect_interval_max;
+        }
+
         // Go back and reconnect.
     }
 }